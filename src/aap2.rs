@@ -0,0 +1,183 @@
+//! Minimal AAP version 2 client.
+//!
+//! AAP v2 replaces AAP v1's fixed preamble/type-byte framing with a
+//! length-delimited message frame (a big-endian `u32` byte count, a single
+//! message-type byte, then the body), which lets a node describe endpoints
+//! with a scheme prefix (`dtn://`, `ipn:`, ...) instead of the bare
+//! singleton-relative agent ids AAP v1 assumes.
+//!
+//! `ud3tn_aap` only implements AAP v1, so this module speaks just enough of
+//! the v2 wire format for `bundlecat` to say hello, register an agent, and
+//! send/receive bundles against a v2-only node.
+
+use std::io::{self, Read, Write};
+
+const MSG_WELCOME: u8 = 0x01;
+const MSG_REGISTER: u8 = 0x02;
+const MSG_ACK: u8 = 0x03;
+const MSG_SENDBUNDLE: u8 = 0x04;
+const MSG_RECVBUNDLE: u8 = 0x05;
+const MSG_NACK: u8 = 0x06;
+
+/// Upper bound on a single frame body, to avoid a misbehaving or
+/// misidentified peer driving an unbounded allocation.
+const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    UnexpectedMessage(u8),
+    FrameTooLarge(usize),
+    Truncated,
+    DestinationTooLong(usize),
+    Nack,
+    Eof,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::UnexpectedMessage(t) => write!(f, "unexpected AAPv2 message type {t:#04x}"),
+            Error::FrameTooLarge(len) => write!(f, "AAPv2 frame of {len} bytes exceeds the maximum"),
+            Error::Truncated => write!(f, "RECVBUNDLE frame is too short for its declared source length"),
+            Error::DestinationTooLong(len) => write!(f, "destination EID of {len} bytes exceeds the 65535-byte SENDBUNDLE length field"),
+            Error::Nack => write!(f, "node rejected the request (NACK)"),
+            Error::Eof => write!(f, "connection closed by node"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+pub struct Bundle {
+    pub source: Option<String>,
+    pub payload: Vec<u8>,
+}
+
+fn write_frame<S: Write>(stream: &mut S, msg_type: u8, body: &[u8]) -> Result<(), Error> {
+    stream.write_all(&(body.len() as u32).to_be_bytes())?;
+    stream.write_all(&[msg_type])?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn read_frame<S: Read>(stream: &mut S) -> Result<(u8, Vec<u8>), Error> {
+    let mut len_buf = [0u8; 4];
+    stream.read_exact(&mut len_buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => Error::Eof,
+        _ => Error::Io(e),
+    })?;
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(Error::FrameTooLarge(len));
+    }
+
+    let mut msg_type = [0u8; 1];
+    stream.read_exact(&mut msg_type)?;
+
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body)?;
+
+    Ok((msg_type[0], body))
+}
+
+/// A connected, not-yet-registered AAPv2 agent.
+pub struct Agent2<S> {
+    stream: S,
+    node_id: String,
+}
+
+impl<S: Read + Write> Agent2<S> {
+    /// Reads the node's welcome frame off `stream` and returns a handle
+    /// bound to it, mirroring `ud3tn_aap::Agent::new` for the v1 transport.
+    pub fn new(mut stream: S) -> Result<Self, Error> {
+        let (msg_type, body) = read_frame(&mut stream)?;
+        if msg_type != MSG_WELCOME {
+            return Err(Error::UnexpectedMessage(msg_type));
+        }
+        let node_id = String::from_utf8_lossy(&body).into_owned();
+        Ok(Self { stream, node_id })
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    pub fn register(mut self, agent_id: String) -> Result<RegisteredAgent2<S>, Error> {
+        write_frame(&mut self.stream, MSG_REGISTER, agent_id.as_bytes())?;
+        match read_frame(&mut self.stream)? {
+            (MSG_ACK, _) => Ok(RegisteredAgent2 {
+                stream: self.stream,
+                node_id: self.node_id,
+                agent_id,
+            }),
+            (MSG_NACK, _) => Err(Error::Nack),
+            (other, _) => Err(Error::UnexpectedMessage(other)),
+        }
+    }
+}
+
+/// An AAPv2 agent registered on an endpoint, able to send and receive bundles.
+pub struct RegisteredAgent2<S> {
+    stream: S,
+    node_id: String,
+    agent_id: String,
+}
+
+impl<S: Read + Write> RegisteredAgent2<S> {
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    pub fn agent_id(&self) -> &str {
+        &self.agent_id
+    }
+
+    pub fn send_bundle(&mut self, destination: String, payload: &[u8]) -> Result<(), Error> {
+        if destination.len() > u16::MAX as usize {
+            return Err(Error::DestinationTooLong(destination.len()));
+        }
+
+        let mut body = Vec::with_capacity(2 + destination.len() + payload.len());
+        body.extend_from_slice(&(destination.len() as u16).to_be_bytes());
+        body.extend_from_slice(destination.as_bytes());
+        body.extend_from_slice(payload);
+
+        write_frame(&mut self.stream, MSG_SENDBUNDLE, &body)?;
+        match read_frame(&mut self.stream)? {
+            (MSG_ACK, _) => Ok(()),
+            (MSG_NACK, _) => Err(Error::Nack),
+            (other, _) => Err(Error::UnexpectedMessage(other)),
+        }
+    }
+
+    pub fn recv_bundle(&mut self) -> Result<Bundle, Error> {
+        let (msg_type, body) = match read_frame(&mut self.stream)? {
+            (MSG_RECVBUNDLE, body) => (MSG_RECVBUNDLE, body),
+            (other, _) => return Err(Error::UnexpectedMessage(other)),
+        };
+
+        if body.len() < 2 {
+            return Err(Error::UnexpectedMessage(msg_type));
+        }
+        let source_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+        if 2 + source_len > body.len() {
+            return Err(Error::Truncated);
+        }
+        let source = (source_len > 0)
+            .then(|| String::from_utf8_lossy(&body[2..2 + source_len]).into_owned());
+        let payload = body[2 + source_len..].to_vec();
+
+        write_frame(&mut self.stream, MSG_ACK, &[])?;
+
+        Ok(Bundle { source, payload })
+    }
+}
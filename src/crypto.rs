@@ -0,0 +1,113 @@
+//! Optional end-to-end payload encryption.
+//!
+//! Bundles are stored and forwarded through intermediaries, so payload
+//! confidentiality can't rely on the transport being trusted. This module
+//! implements an anonymous sealed-box construction: the sender generates an
+//! ephemeral X25519 keypair, derives a shared secret with the recipient's
+//! public key, derives a nonce from both public keys, and seals the payload
+//! with XChaCha20-Poly1305. The ephemeral public key and a version byte are
+//! prepended to the ciphertext so the result is self-describing.
+
+use base64::Engine;
+use chacha20poly1305::{aead::Aead, KeyInit, XChaCha20Poly1305, XNonce};
+use blake2::digest::Digest;
+use blake2::Blake2b512;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+/// Identifies the sealing scheme used by a given payload, so a future AEAD
+/// change doesn't silently corrupt data sealed under an older one.
+const VERSION: u8 = 1;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidKeyEncoding,
+    InvalidKeyLength(usize),
+    Truncated,
+    UnsupportedVersion(u8),
+    DecryptionFailed,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidKeyEncoding => write!(f, "key is neither valid hex nor valid base64"),
+            Error::InvalidKeyLength(len) => write!(f, "key must be {KEY_LEN} bytes, got {len}"),
+            Error::Truncated => write!(f, "sealed payload is too short to contain a header"),
+            Error::UnsupportedVersion(v) => write!(f, "sealed payload uses unsupported scheme version {v}"),
+            Error::DecryptionFailed => write!(f, "failed to decrypt payload: authentication tag mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Parses a key given as either hex or base64 on the command line.
+pub fn parse_key(input: &str) -> Result<[u8; KEY_LEN], Error> {
+    let bytes = hex::decode(input)
+        .or_else(|_| base64::engine::general_purpose::STANDARD.decode(input))
+        .map_err(|_| Error::InvalidKeyEncoding)?;
+
+    bytes.try_into().map_err(|bytes: Vec<u8>| Error::InvalidKeyLength(bytes.len()))
+}
+
+/// Derives the sealing nonce as `blake2b(ephemeral_pk || recipient_pk)`
+/// truncated to 24 bytes, i.e. the leading bytes of the standard 64-byte
+/// digest, not a distinct 24-byte-output BLAKE2b variant.
+fn derive_nonce(ephemeral_pk: &[u8; KEY_LEN], recipient_pk: &[u8; KEY_LEN]) -> [u8; NONCE_LEN] {
+    let mut hasher = Blake2b512::new();
+    hasher.update(ephemeral_pk);
+    hasher.update(recipient_pk);
+
+    let digest = hasher.finalize();
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce.copy_from_slice(&digest[..NONCE_LEN]);
+    nonce
+}
+
+/// Seals `payload` so only the holder of `recipient_pk`'s private key can
+/// read it, prepending the ephemeral public key used for this message.
+pub fn seal(payload: &[u8], recipient_pk: &[u8; KEY_LEN]) -> Vec<u8> {
+    let ephemeral_secret = EphemeralSecret::random();
+    let ephemeral_pk = PublicKey::from(&ephemeral_secret);
+    let shared_secret = ephemeral_secret.diffie_hellman(&PublicKey::from(*recipient_pk));
+
+    let nonce = derive_nonce(ephemeral_pk.as_bytes(), recipient_pk);
+    let cipher = XChaCha20Poly1305::new(shared_secret.as_bytes().into());
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), payload)
+        .expect("XChaCha20-Poly1305 encryption is infallible for in-memory buffers");
+
+    let mut sealed = Vec::with_capacity(1 + KEY_LEN + ciphertext.len());
+    sealed.push(VERSION);
+    sealed.extend_from_slice(ephemeral_pk.as_bytes());
+    sealed.extend_from_slice(&ciphertext);
+    sealed
+}
+
+/// Opens a payload sealed with [`seal`], given the recipient's private key.
+pub fn open(sealed: &[u8], recipient_sk: &[u8; KEY_LEN]) -> Result<Vec<u8>, Error> {
+    if sealed.len() < 1 + KEY_LEN {
+        return Err(Error::Truncated);
+    }
+
+    let version = sealed[0];
+    if version != VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+
+    let ephemeral_pk: [u8; KEY_LEN] = sealed[1..1 + KEY_LEN].try_into().unwrap();
+    let ciphertext = &sealed[1 + KEY_LEN..];
+
+    let secret = StaticSecret::from(*recipient_sk);
+    let recipient_pk = PublicKey::from(&secret);
+    let shared_secret = secret.diffie_hellman(&PublicKey::from(ephemeral_pk));
+
+    let nonce = derive_nonce(&ephemeral_pk, recipient_pk.as_bytes());
+    let cipher = XChaCha20Poly1305::new(shared_secret.as_bytes().into());
+    cipher
+        .decrypt(XNonce::from_slice(&nonce), ciphertext)
+        .map_err(|_| Error::DecryptionFailed)
+}
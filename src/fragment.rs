@@ -0,0 +1,168 @@
+//! Splitting a large payload into ordered, bundle-sized fragments and
+//! reassembling them back into the original payload on the receiving end.
+//!
+//! Each fragment is its own bundle whose body is a small fixed header
+//! (transfer id, chunk index, chunk count, total payload length) followed
+//! by that chunk's bytes, so fragments can be told apart from other
+//! concurrent transfers and reassembled in order without any side channel.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const HEADER_LEN: usize = 16 + 4 + 4 + 8;
+
+/// Generous upper bound on a transfer's declared total length and fragment
+/// count, so a single malformed or malicious fragment header can't drive an
+/// unbounded allocation (or an absurdly long reassembly loop) before any
+/// actual payload bytes back it up.
+const MAX_TRANSFER_LEN: u64 = 1024 * 1024 * 1024;
+const MAX_FRAGMENT_COUNT: u32 = 1_000_000;
+
+/// Splits `payload` into ordered chunks of at most `fragment_size` bytes
+/// each and prefixes every chunk with a `HEADER_LEN`-byte fragment header
+/// tagged with `transfer_id`.
+pub fn split(payload: &[u8], fragment_size: usize, transfer_id: uuid::Uuid) -> Vec<Vec<u8>> {
+    let fragment_size = fragment_size.max(1);
+    let chunks: Vec<&[u8]> = if payload.is_empty() {
+        vec![&payload[..]]
+    } else {
+        payload.chunks(fragment_size).collect()
+    };
+    let total_count = chunks.len() as u32;
+    let total_len = payload.len() as u64;
+
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| encode_fragment(transfer_id, index as u32, total_count, total_len, chunk))
+        .collect()
+}
+
+fn encode_fragment(transfer_id: uuid::Uuid, index: u32, total_count: u32, total_len: u64, chunk: &[u8]) -> Vec<u8> {
+    let mut fragment = Vec::with_capacity(HEADER_LEN + chunk.len());
+    fragment.extend_from_slice(transfer_id.as_bytes());
+    fragment.extend_from_slice(&index.to_be_bytes());
+    fragment.extend_from_slice(&total_count.to_be_bytes());
+    fragment.extend_from_slice(&total_len.to_be_bytes());
+    fragment.extend_from_slice(chunk);
+    fragment
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Truncated,
+    ImplausibleTransferSize { total_len: u64, total_count: u32 },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Truncated => write!(f, "fragment is too short to contain a header"),
+            Error::ImplausibleTransferSize { total_len, total_count } => write!(f,
+                "fragment declares an implausible transfer size (total_len={total_len}, total_count={total_count})"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+struct DecodedFragment {
+    transfer_id: uuid::Uuid,
+    index: u32,
+    total_count: u32,
+    total_len: u64,
+    chunk: Vec<u8>,
+}
+
+fn decode_fragment(bytes: &[u8]) -> Result<DecodedFragment, Error> {
+    if bytes.len() < HEADER_LEN {
+        return Err(Error::Truncated);
+    }
+
+    let transfer_id = uuid::Uuid::from_slice(&bytes[0..16]).expect("slice is exactly 16 bytes");
+    let index = u32::from_be_bytes(bytes[16..20].try_into().unwrap());
+    let total_count = u32::from_be_bytes(bytes[20..24].try_into().unwrap());
+    let total_len = u64::from_be_bytes(bytes[24..32].try_into().unwrap());
+    let chunk = bytes[HEADER_LEN..].to_vec();
+
+    if total_len > MAX_TRANSFER_LEN || total_count > MAX_FRAGMENT_COUNT {
+        return Err(Error::ImplausibleTransferSize { total_len, total_count });
+    }
+
+    Ok(DecodedFragment { transfer_id, index, total_count, total_len, chunk })
+}
+
+struct Transfer {
+    total_count: u32,
+    total_len: u64,
+    chunks: HashMap<u32, Vec<u8>>,
+    last_activity: Instant,
+}
+
+/// What feeding a fragment into a [`Reassembler`] produced.
+pub struct Fed {
+    /// The reassembled payload, once the fed fragment completed its transfer.
+    pub payload: Option<Vec<u8>>,
+    /// Transfers that were garbage-collected for exceeding the timeout.
+    /// These are unrelated to the fragment just fed.
+    pub expired: Vec<uuid::Uuid>,
+}
+
+/// Buffers fragments across one or more received bundles until a full
+/// transfer is reassembled.
+///
+/// Timeout tracking is best-effort: a stalled transfer is only noticed once
+/// another fragment comes in and ages are checked, since there is no way to
+/// interrupt a bundle receive that is already blocked waiting on the node.
+#[derive(Default)]
+pub struct Reassembler {
+    transfers: HashMap<uuid::Uuid, Transfer>,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds one received bundle's payload in. Returns the reassembled
+    /// payload once its transfer is complete, or `None` while more
+    /// fragments are still expected, alongside any unrelated transfers
+    /// dropped for exceeding `timeout`.
+    pub fn feed(&mut self, bytes: &[u8], timeout: Duration) -> Result<Fed, Error> {
+        let fragment = decode_fragment(bytes)?;
+
+        // Exclude the transfer this fragment belongs to: it's about to have
+        // its activity refreshed below, so it must never be judged stale
+        // against its own pre-update age, no matter how long it's been open.
+        let expired: Vec<uuid::Uuid> = self.transfers.iter()
+            .filter(|(id, transfer)| **id != fragment.transfer_id && transfer.last_activity.elapsed() >= timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        for stale_id in &expired {
+            self.transfers.remove(stale_id);
+        }
+
+        let transfer = self.transfers.entry(fragment.transfer_id).or_insert_with(|| Transfer {
+            total_count: fragment.total_count,
+            total_len: fragment.total_len,
+            chunks: HashMap::new(),
+            last_activity: Instant::now(),
+        });
+        transfer.last_activity = Instant::now();
+
+        transfer.chunks.insert(fragment.index, fragment.chunk);
+
+        if transfer.chunks.len() as u32 >= transfer.total_count {
+            let transfer = self.transfers.remove(&fragment.transfer_id).unwrap();
+            let mut payload = Vec::with_capacity(transfer.total_len as usize);
+            for index in 0..transfer.total_count {
+                if let Some(chunk) = transfer.chunks.get(&index) {
+                    payload.extend_from_slice(chunk);
+                }
+            }
+            return Ok(Fed { payload: Some(payload), expired });
+        }
+
+        Ok(Fed { payload: None, expired })
+    }
+}
@@ -1,6 +1,11 @@
-use std::{io::{stdin, stdout, Read, Write}, os::unix::net::UnixStream, path::PathBuf};
+use std::{io::{stdin, stdout, Read, Write}, net::TcpStream, os::unix::net::UnixStream, path::PathBuf};
 use ud3tn_aap::{AapStream, Agent, BaseAgent, RegisteredAgent};
-use clap::{CommandFactory, Parser};
+use clap::{Parser, ValueEnum};
+use base64::Engine;
+
+mod aap2;
+mod crypto;
+mod fragment;
 
 #[derive(Debug, Parser)]
 #[command(version, about = "Send and Receive bundles with archipel/ud3tn", long_about = None)]
@@ -13,6 +18,16 @@ struct Cli {
     #[arg(short, long)]
     listen: bool,
 
+    /// Keep the agent registered and receive bundles continuously instead
+    /// of exiting after the first one
+    ///
+    /// Each bundle is written as its own frame so consumers can tell them
+    /// apart: a 4-byte big-endian length prefix followed by the payload in
+    /// `text` format, or one JSON object per line (NDJSON) in `json`
+    /// format.
+    #[arg(long = "keep-alive", requires = "listen")]
+    keep_alive: bool,
+
     // When sending bundle, name of source endpoint id to send bundle from
     //
     // Formatted as dtn://<node_id>/<agent_id>
@@ -30,9 +45,75 @@ struct Cli {
     #[arg(required_unless_present("listen"))]
     endpoint_id: Option<PartialEndpointId>,
 
-    /// Archipel/ud3tn AAP (version 1) socket
-    #[arg(long, default_value = "/run/archipel-core/archipel-core.socket")]
+    /// Archipel/ud3tn AAP socket
+    #[arg(long, default_value = "/run/archipel-core/archipel-core.socket", conflicts_with = "node_tcp")]
     node_sock: PathBuf,
+
+    /// Connect to a remote archipel/ud3tn node's AAP endpoint over TCP
+    /// instead of a local socket, as `host:port`
+    #[arg(long = "node-tcp", conflicts_with = "node_sock")]
+    node_tcp: Option<String>,
+
+    /// Encrypt the outgoing bundle payload for this recipient's X25519
+    /// public key (base64 or hex) using an anonymous sealed-box scheme
+    #[arg(long = "encrypt-to", conflicts_with = "decrypt_with")]
+    encrypt_to: Option<String>,
+
+    /// Decrypt received bundle payloads sealed with `--encrypt-to`, using
+    /// this X25519 private key (base64 or hex)
+    #[arg(long = "decrypt-with", conflicts_with = "encrypt_to", requires = "listen")]
+    decrypt_with: Option<String>,
+
+    /// Split the outgoing payload into ordered chunks of at most this
+    /// many bytes, each sent as its own bundle behind a 32-byte fragment
+    /// header, for payloads larger than a node's bundle-size limit
+    #[arg(long = "fragment-size", conflicts_with = "listen")]
+    fragment_size: Option<usize>,
+
+    /// Reassemble a payload that was split with `--fragment-size` before
+    /// writing it to stdout
+    #[arg(long, requires = "listen")]
+    reassemble: bool,
+
+    /// How long to wait for missing fragments of a transfer before
+    /// erroring out, in seconds
+    #[arg(long = "fragment-timeout", default_value = "30", requires = "reassemble")]
+    fragment_timeout: u64,
+
+    /// AAP protocol version to speak with the node
+    ///
+    /// `auto` probes the node's welcome banner and picks the highest
+    /// version it offers.
+    #[arg(long = "aap-version", default_value = "auto")]
+    aap_version: AapVersion,
+
+    /// Output format for received bundles and for error reporting
+    #[arg(long, default_value = "text")]
+    format: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum AapVersion {
+    #[value(name = "1")]
+    V1,
+    #[value(name = "2")]
+    V2,
+    Auto,
+}
+
+/// Renders a concrete (non-`Auto`) version for log and error messages.
+fn aap_version_name(version: AapVersion) -> &'static str {
+    match version {
+        AapVersion::V1 => "1",
+        AapVersion::V2 => "2",
+        AapVersion::Auto => unreachable!("Auto is never the offered or negotiated version"),
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -86,63 +167,283 @@ macro_rules! log {
     }};
 }
 
+/// Exit code used when `--aap-version` requests a version the node doesn't
+/// offer, or the welcome banner can't be read from the node.
+const AAP_VERSION_EXIT_CODE: i32 = 12;
+
+/// Reports an error in the requested `--format` and exits with `code`.
+///
+/// In `text` mode this is a plain `eprintln!`; in `json` mode it prints a
+/// `{"error": "...", "code": N}` object instead, so scripts driving
+/// `bundlecat` can parse failures the same way they parse successes.
+fn fail(format: OutputFormat, message: impl std::fmt::Display, code: i32) -> ! {
+    match format {
+        OutputFormat::Text => eprintln!("{message}"),
+        OutputFormat::Json => eprintln!("{}", serde_json::json!({
+            "error": message.to_string(),
+            "code": code,
+        })),
+    }
+    std::process::exit(code);
+}
+
+#[derive(Debug)]
+enum AapError {
+    V1(ud3tn_aap::Error),
+    V2(aap2::Error),
+}
+
+impl std::fmt::Display for AapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AapError::V1(e) => write!(f, "{e}"),
+            AapError::V2(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Wraps a stream so a handful of leading bytes can be inspected (to guess
+/// the AAP version the node speaks) and then replayed to whichever client
+/// ends up reading the stream for real.
+struct PeekableStream<S> {
+    inner: S,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<S: Read> PeekableStream<S> {
+    fn new(inner: S) -> Self {
+        Self { inner, buf: Vec::new(), pos: 0 }
+    }
+
+    /// Ensures at least `n` bytes (or as many as are available before EOF)
+    /// are buffered, then returns everything buffered so far.
+    fn peek(&mut self, n: usize) -> std::io::Result<&[u8]> {
+        while self.buf.len() - self.pos < n {
+            let mut byte = [0u8; 1];
+            match self.inner.read(&mut byte)? {
+                0 => break,
+                _ => self.buf.push(byte[0]),
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+}
+
+impl<S: Read> Read for PeekableStream<S> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos < self.buf.len() {
+            let n = out.len().min(self.buf.len() - self.pos);
+            out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        } else {
+            self.inner.read(out)
+        }
+    }
+}
+
+impl<S: Write> Write for PeekableStream<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: AapStream> AapStream for PeekableStream<S> {}
+
+/// The socket `bundlecat` connects to the node over, either a local AAP
+/// socket or a remote node's AAP endpoint reached over TCP.
+enum Transport {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Read for Transport {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Unix(s) => s.read(out),
+            Transport::Tcp(s) => s.read(out),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Unix(s) => s.write(buf),
+            Transport::Tcp(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Transport::Unix(s) => s.flush(),
+            Transport::Tcp(s) => s.flush(),
+        }
+    }
+}
+
+impl AapStream for Transport {}
+
+/// Known AAP v2 message types; used to tell a v2 welcome frame apart from a
+/// v1 welcome line when sniffing the banner in `auto` mode.
+const AAP2_MESSAGE_TYPES: std::ops::RangeInclusive<u8> = 0x01..=0x06;
+
+/// Peeks at the node's welcome banner to tell which AAP version it speaks.
+///
+/// AAP v1's welcome is a bare node id string; AAP v2's is a length-delimited
+/// frame, so the 5th byte sniffed off the wire is a known message-type byte
+/// only for v2.
+fn sniff_aap_version<S: Read>(stream: &mut PeekableStream<S>) -> std::io::Result<AapVersion> {
+    let prefix = stream.peek(5)?;
+    if let [_, _, _, _, msg_type] = prefix {
+        if AAP2_MESSAGE_TYPES.contains(msg_type) {
+            return Ok(AapVersion::V2);
+        }
+    }
+    Ok(AapVersion::V1)
+}
+
+/// A connected, not-yet-registered agent speaking either AAP version.
+enum AnyAgent<S: AapStream> {
+    V1(Agent<S>),
+    V2(aap2::Agent2<S>),
+}
+
+impl<S: AapStream> AnyAgent<S> {
+    fn node_id(&self) -> &str {
+        match self {
+            AnyAgent::V1(a) => a.node_id(),
+            AnyAgent::V2(a) => a.node_id(),
+        }
+    }
+
+    fn register(self, agent_id: String) -> Result<AnyRegisteredAgent<S>, AapError> {
+        match self {
+            AnyAgent::V1(a) => a.register(agent_id).map(AnyRegisteredAgent::V1).map_err(AapError::V1),
+            AnyAgent::V2(a) => a.register(agent_id).map(AnyRegisteredAgent::V2).map_err(AapError::V2),
+        }
+    }
+}
+
+/// An agent registered on an endpoint, able to send and receive bundles
+/// over either AAP version.
+enum AnyRegisteredAgent<S: AapStream> {
+    V1(RegisteredAgent<S>),
+    V2(aap2::RegisteredAgent2<S>),
+}
+
+impl<S: AapStream> AnyRegisteredAgent<S> {
+    fn node_id(&self) -> &str {
+        match self {
+            AnyRegisteredAgent::V1(a) => a.node_id(),
+            AnyRegisteredAgent::V2(a) => a.node_id(),
+        }
+    }
+
+    fn agent_id(&self) -> &str {
+        match self {
+            AnyRegisteredAgent::V1(a) => a.agent_id(),
+            AnyRegisteredAgent::V2(a) => a.agent_id(),
+        }
+    }
+
+    fn send_bundle(&mut self, destination: String, payload: &[u8]) -> Result<(), AapError> {
+        match self {
+            AnyRegisteredAgent::V1(a) => a.send_bundle(destination, payload).map_err(AapError::V1),
+            AnyRegisteredAgent::V2(a) => a.send_bundle(destination, payload).map_err(AapError::V2),
+        }
+    }
+
+    fn recv_bundle(&mut self) -> Result<aap2::Bundle, AapError> {
+        match self {
+            AnyRegisteredAgent::V1(a) => a.recv_bundle().map(|b| aap2::Bundle { source: b.source, payload: b.payload }).map_err(AapError::V1),
+            AnyRegisteredAgent::V2(a) => a.recv_bundle().map_err(AapError::V2),
+        }
+    }
+}
+
 fn main() {
     let cli = Cli::parse();
     let verbose = cli.verbose;
 
     if ! cli.listen {
-        let mut cmd = Cli::command();
         if cli.endpoint_id.as_ref().is_none_or(|it| it.node_id().is_none()) {
-            cmd.error(
-                clap::error::ErrorKind::MissingRequiredArgument,
-                "When sending a bundle, destination eid must contains a node_id part".to_string())
-                .exit();
+            fail(cli.format, "When sending a bundle, destination eid must contains a node_id part", 2);
         } else if cli.endpoint_id.as_ref().is_none_or(|it| it.agent_id().is_none()) {
-            cmd.error(
-                clap::error::ErrorKind::MissingRequiredArgument,
-                "When sending a bundle, destination eid must contains a agent_id part".to_string())
-                .exit();
+            fail(cli.format, "When sending a bundle, destination eid must contains a agent_id part", 2);
         } else if cli.source_endpoint_id.as_ref().is_some_and(|it| !it.is_singleton_node()) {
-            eprintln!("Sending bundle from non-singleton node_id is not supported");
-            std::process::exit(1);
+            fail(cli.format, "Sending bundle from non-singleton node_id is not supported", 1);
         }
     } else if cli.endpoint_id.as_ref().is_some_and(|it| !it.is_singleton_node()) {
-        eprintln!("Listening on non-singleton node_id is not supported");
-        std::process::exit(1);
+        fail(cli.format, "Listening on non-singleton node_id is not supported", 1);
     }
 
-    let unix_stream = match UnixStream::connect(cli.node_sock.clone()) {
-        Err(e) => {
-            eprintln!("Failed to connect to node socket: {e}");
-            std::process::exit(10);
-        },
-        Ok(s) => s
+    let encrypt_to = cli.encrypt_to.as_deref().map(|key| match crypto::parse_key(key) {
+        Ok(key) => key,
+        Err(e) => fail(cli.format, format_args!("Invalid --encrypt-to key: {e}"), 17),
+    });
+    let decrypt_with = cli.decrypt_with.as_deref().map(|key| match crypto::parse_key(key) {
+        Ok(key) => key,
+        Err(e) => fail(cli.format, format_args!("Invalid --decrypt-with key: {e}"), 17),
+    });
+
+    let transport = if let Some(addr) = cli.node_tcp.as_ref() {
+        match TcpStream::connect(addr.as_str()) {
+            Err(e) => fail(cli.format, format_args!("Failed to connect to node over TCP: {e}"), 10),
+            Ok(s) => Transport::Tcp(s),
+        }
+    } else {
+        match UnixStream::connect(cli.node_sock.clone()) {
+            Err(e) => fail(cli.format, format_args!("Failed to connect to node socket: {e}"), 10),
+            Ok(s) => Transport::Unix(s),
+        }
+    };
+
+    log!(verbose, "Connected to node on {}", cli.node_tcp.clone().unwrap_or_else(|| cli.node_sock.to_string_lossy().into_owned()));
+
+    let mut peekable_stream = PeekableStream::new(transport);
+    let offered_version = match sniff_aap_version(&mut peekable_stream) {
+        Ok(v) => v,
+        Err(e) => fail(cli.format, format_args!("Failed to read welcome banner from node: {e}"), AAP_VERSION_EXIT_CODE),
     };
 
-    log!(verbose, "Connected to node on {}", cli.node_sock.to_string_lossy());
+    let negotiated_version = match cli.aap_version {
+        AapVersion::Auto => offered_version,
+        requested if requested == offered_version => requested,
+        requested => fail(cli.format, format_args!(
+            "Requested AAP version {} isn't offered by this node (it offers version {})",
+            aap_version_name(requested), aap_version_name(offered_version)), AAP_VERSION_EXIT_CODE),
+    };
+    log!(verbose, "Using AAP version {}", aap_version_name(negotiated_version));
 
-    let agent = match Agent::new(unix_stream) {
-        Err(e) => {
-            eprint!("Failed to establish a connection with node: {e}");
-            std::process::exit(11);
+    let agent = match negotiated_version {
+        AapVersion::V1 => match Agent::new(peekable_stream) {
+            Err(e) => fail(cli.format, format_args!("Failed to establish a connection with node: {e}"), 11),
+            Ok(a) => AnyAgent::V1(a),
         },
-        Ok(a) => {
-            log!(verbose, "Welcome from node {}", a.node_id());
-            a
-        }
+        AapVersion::V2 => match aap2::Agent2::new(peekable_stream) {
+            Err(e) => fail(cli.format, format_args!("Failed to establish a connection with node: {e}"), 11),
+            Ok(a) => AnyAgent::V2(a),
+        },
+        AapVersion::Auto => unreachable!(),
     };
+    log!(verbose, "Welcome from node {}", agent.node_id());
 
-    if (cli.listen && 
+    if (cli.listen &&
             cli.endpoint_id.as_ref().is_some_and(
                 |it| it.node_id().is_some_and(|it| it != agent.node_id()))) ||
         cli.source_endpoint_id.as_ref().is_some_and(
             |it| it.node_id()
     .is_some_and(|it| it != agent.node_id())) {
-        eprintln!("Provided node id is different from node id configured on server ({})", agent.node_id());
-        std::process::exit(2);
+        fail(cli.format, format_args!("Provided node id is different from node id configured on server ({})", agent.node_id()), 2);
     }
 
-    let agent_id = (if cli.listen { 
+    let agent_id = (if cli.listen {
         cli.endpoint_id.clone()
     } else {
         cli.source_endpoint_id.clone()
@@ -151,10 +452,7 @@ fn main() {
     .unwrap_or_else(|| uuid::Uuid::new_v4().to_string() );
 
     let agent = match agent.register(agent_id.clone()) {
-        Err(e) => {
-            eprint!("Failed to establish a connection with node: {e}");
-            std::process::exit(11);
-        },
+        Err(e) => fail(cli.format, format_args!("Failed to establish a connection with node: {e}"), 11),
         Ok(a) => {
             log!(verbose, "Agent registered on endpoint {}{}", a.node_id(), a.agent_id());
             a
@@ -163,14 +461,14 @@ fn main() {
 
 
     if cli.listen {
-        receive(agent, verbose);
+        receive(agent, verbose, cli.format, cli.keep_alive, decrypt_with, cli.reassemble, std::time::Duration::from_secs(cli.fragment_timeout));
     } else {
         let destination_eid = cli.endpoint_id.unwrap();
-        send(agent, verbose, destination_eid);
+        send(agent, verbose, cli.format, destination_eid, encrypt_to, cli.fragment_size);
     }
 }
 
-fn send<S: AapStream>(mut agent: RegisteredAgent<S>, verbose: bool, destination: PartialEndpointId){
+fn send<S: AapStream>(mut agent: AnyRegisteredAgent<S>, verbose: bool, format: OutputFormat, destination: PartialEndpointId, encrypt_to: Option<[u8; 32]>, fragment_size: Option<usize>){
 
     let destination_eid = destination.0;
 
@@ -180,10 +478,7 @@ fn send<S: AapStream>(mut agent: RegisteredAgent<S>, verbose: bool, destination:
     loop {
         let byte_red = match stdin().read(&mut buffer) {
             Ok(result) => result,
-            Err(e) => {
-                eprintln!("Failed to read from stdin: {e}");
-                std::process::exit(13);
-            }
+            Err(e) => fail(format, format_args!("Failed to read from stdin: {e}"), 13),
         };
 
         if byte_red > 0 {
@@ -195,31 +490,110 @@ fn send<S: AapStream>(mut agent: RegisteredAgent<S>, verbose: bool, destination:
 
     let bundle_size = bundle_content.len();
 
-    if let Err(e) = agent.send_bundle(destination_eid.clone(), &bundle_content) {
-        eprint!("Failed to send bundle: {e}");
-        std::process::exit(14);
-    }
+    let bundle_content = match encrypt_to {
+        Some(recipient_pk) => crypto::seal(&bundle_content, &recipient_pk),
+        None => bundle_content,
+    };
 
-    log!(verbose, "Sent {} byte bundle to {}", bundle_size, destination_eid);
-}
+    match fragment_size {
+        Some(fragment_size) => {
+            let transfer_id = uuid::Uuid::new_v4();
+            let fragments = fragment::split(&bundle_content, fragment_size, transfer_id);
+            let fragment_count = fragments.len();
 
-fn receive<S: AapStream>(mut agent: RegisteredAgent<S>, verbose: bool){
-    let mut bundle = match agent.recv_bundle() {
-        Ok(b) => b,
-        Err(e) => {
-            eprintln!("Failed to receive bundle: {e}");
-            std::process::exit(15);
+            for fragment in fragments {
+                if let Err(e) = agent.send_bundle(destination_eid.clone(), &fragment) {
+                    fail(format, format_args!("Failed to send bundle fragment: {e}"), 14);
+                }
+            }
+
+            log!(verbose, "Sent {} byte bundle to {} as {} fragments (transfer {})", bundle_size, destination_eid, fragment_count, transfer_id);
         }
-    };
+        None => {
+            if let Err(e) = agent.send_bundle(destination_eid.clone(), &bundle_content) {
+                fail(format, format_args!("Failed to send bundle: {e}"), 14);
+            }
 
-    if let Some(source) = bundle.source.as_ref() {
-        log!(verbose, "Received bundle from {}", source);
-    } else {
-        log!(verbose, "Received bundle from unknown source");
+            log!(verbose, "Sent {} byte bundle to {}", bundle_size, destination_eid);
+        }
     }
+}
+
+fn receive<S: AapStream>(mut agent: AnyRegisteredAgent<S>, verbose: bool, format: OutputFormat, keep_alive: bool, decrypt_with: Option<[u8; 32]>, reassemble: bool, fragment_timeout: std::time::Duration){
+    let mut reassembler = fragment::Reassembler::new();
+
+    loop {
+        let mut bundle = match agent.recv_bundle() {
+            Ok(b) => b,
+            Err(e) => fail(format, format_args!("Failed to receive bundle: {e}"), 15),
+        };
+
+        if reassemble {
+            let fed = match reassembler.feed(&bundle.payload, fragment_timeout) {
+                Ok(fed) => fed,
+                Err(e) => fail(format, format_args!("Failed to reassemble bundle: {e}"), 19),
+            };
+
+            if let Some(expired_transfer) = fed.expired.first() {
+                fail(format, format_args!("Timed out waiting for missing fragments of transfer {expired_transfer}"), 19);
+            }
+
+            match fed.payload {
+                Some(payload) => bundle.payload = payload,
+                None => {
+                    log!(verbose, "Buffered bundle fragment, waiting for the rest of the transfer");
+                    continue;
+                }
+            }
+        }
+
+        if let Some(recipient_sk) = decrypt_with.as_ref() {
+            bundle.payload = match crypto::open(&bundle.payload, recipient_sk) {
+                Ok(payload) => payload,
+                Err(e) => fail(format, format_args!("Failed to decrypt bundle payload: {e}"), 18),
+            };
+        }
+
+        if let Some(source) = bundle.source.as_ref() {
+            log!(verbose, "Received bundle from {}", source);
+        } else {
+            log!(verbose, "Received bundle from unknown source");
+        }
+
+        let destination = format!("{}{}", agent.node_id(), agent.agent_id());
 
-    if let Err(e) = stdout().write_all(&mut bundle.payload) {
-        eprintln!("Failed to write to stdout: {e}");
-        std::process::exit(16);
+        let write_result = match format {
+            // A lone receive keeps writing the raw payload for backward
+            // compatibility; once more bundles can keep arriving, a frame
+            // is needed so consumers can tell them apart.
+            OutputFormat::Text if keep_alive => {
+                let mut out = stdout();
+                out.write_all(&(bundle.payload.len() as u32).to_be_bytes())
+                    .and_then(|_| out.write_all(&bundle.payload))
+            }
+            OutputFormat::Text => stdout().write_all(&bundle.payload),
+            OutputFormat::Json => {
+                let received_at = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                let record = serde_json::json!({
+                    "source": bundle.source,
+                    "destination": destination,
+                    "received_at": received_at,
+                    "payload_len": bundle.payload.len(),
+                    "payload": base64::engine::general_purpose::STANDARD.encode(&bundle.payload),
+                });
+                writeln!(stdout(), "{record}")
+            }
+        };
+
+        if let Err(e) = write_result {
+            fail(format, format_args!("Failed to write to stdout: {e}"), 16);
+        }
+
+        if !keep_alive {
+            break;
+        }
     }
 }
\ No newline at end of file